@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+#[derive(Serialize, Deserialize)]
+struct PersistedDevice {
+    peripheral_id: String,
+}
+
+fn store_path(app: &AppHandle) -> anyhow::Result<PathBuf> {
+    let dir = app.path().app_data_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join("known_device.json"))
+}
+
+/// Persists the stable `PeripheralId` of the device we just connected to (as its `Debug`
+/// string, since btleplug's platform-specific id isn't serializable), so the next launch can
+/// target it directly instead of matching on the (potentially ambiguous) local name. A `BDAddr`
+/// isn't usable here: CoreBluetooth never exposes a real MAC address to the app.
+pub fn save(app: &AppHandle, peripheral_id: &str) -> anyhow::Result<()> {
+    let data = PersistedDevice {
+        peripheral_id: peripheral_id.to_string(),
+    };
+    std::fs::write(store_path(app)?, serde_json::to_vec(&data)?)?;
+
+    Ok(())
+}
+
+pub fn load(app: &AppHandle) -> anyhow::Result<Option<String>> {
+    let path = store_path(app)?;
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let data: PersistedDevice = serde_json::from_slice(&std::fs::read(path)?)?;
+    Ok(Some(data.peripheral_id))
+}
+
+pub fn clear(app: &AppHandle) -> anyhow::Result<()> {
+    let path = store_path(app)?;
+    if path.is_file() {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use anyhow::Context;
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral as _, ValueNotification, WriteType};
+use btleplug::platform::Peripheral;
+use futures::Stream;
+use uuid::Uuid;
+
+use crate::LedConfiguration;
+
+pub const LED_CONFIG_CHAR_UUID: Uuid = Uuid::from_u128(42424242);
+pub const LED_STATUS_CHAR_UUID: Uuid = Uuid::from_u128(42424243);
+
+/// Nordic UART Service characteristics, used for a free-form debug/command console alongside
+/// the structured LED config/status pair.
+pub const UART_RX_CHAR_UUID: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+pub const UART_TX_CHAR_UUID: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+/// Wraps a connected ESP peripheral together with its resolved characteristic handles, so
+/// callers write/subscribe against cached `Characteristic`s instead of re-discovering services
+/// and re-searching `characteristics()` on every call.
+///
+/// Only `config_char` is mandatory: it's all the baseline LED-config-write feature needs. The
+/// status and UART characteristics are optional so boards that don't expose them still connect
+/// and get LED control; their pumps just stay quiet.
+#[derive(Clone)]
+pub struct EspHandler {
+    peripheral: Peripheral,
+    config_char: Characteristic,
+    status_char: Option<Characteristic>,
+    uart_rx_char: Option<Characteristic>,
+    uart_tx_char: Option<Characteristic>,
+}
+
+impl EspHandler {
+    /// Discovers services on `peripheral` and resolves the characteristics this app depends on.
+    /// Only the LED config characteristic is required; the others are resolved on a best-effort
+    /// basis and simply left unset (logging why) if the board doesn't expose them.
+    pub async fn connect(peripheral: Peripheral) -> anyhow::Result<Self> {
+        peripheral.discover_services().await?;
+        let characteristics = peripheral.characteristics();
+
+        let config_char = characteristics
+            .iter()
+            .find(|c| c.uuid == LED_CONFIG_CHAR_UUID)
+            .cloned()
+            .context("LED config characteristic not found")?;
+
+        let status_char = characteristics
+            .iter()
+            .find(|c| {
+                c.uuid == LED_STATUS_CHAR_UUID && c.properties.contains(CharPropFlags::NOTIFY)
+            })
+            .cloned();
+        if status_char.is_none() {
+            println!("LED status characteristic not found, status updates won't be available");
+        }
+
+        let uart_rx_char = characteristics
+            .iter()
+            .find(|c| c.uuid == UART_RX_CHAR_UUID)
+            .cloned();
+        if uart_rx_char.is_none() {
+            println!("UART RX characteristic not found, send_command won't be available");
+        }
+
+        let uart_tx_char = characteristics
+            .iter()
+            .find(|c| c.uuid == UART_TX_CHAR_UUID && c.properties.contains(CharPropFlags::NOTIFY))
+            .cloned();
+        if uart_tx_char.is_none() {
+            println!("UART TX characteristic not found, command-line output won't be available");
+        }
+
+        Ok(Self {
+            peripheral,
+            config_char,
+            status_char,
+            uart_rx_char,
+            uart_tx_char,
+        })
+    }
+
+    pub fn peripheral(&self) -> &Peripheral {
+        &self.peripheral
+    }
+
+    pub async fn write_led_config(&self, config: &LedConfiguration) -> anyhow::Result<()> {
+        let bytes = bincode::serde::encode_to_vec(config, bincode::config::standard())?;
+        self.peripheral
+            .write(&self.config_char, &bytes, WriteType::WithoutResponse)
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn status_uuid(&self) -> Option<Uuid> {
+        self.status_char.as_ref().map(|c| c.uuid)
+    }
+
+    pub async fn send_command(&self, text: &str) -> anyhow::Result<()> {
+        let uart_rx_char = self
+            .uart_rx_char
+            .as_ref()
+            .context("UART RX characteristic not available")?;
+        self.peripheral
+            .write(uart_rx_char, text.as_bytes(), WriteType::WithoutResponse)
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn uart_tx_uuid(&self) -> Option<Uuid> {
+        self.uart_tx_char.as_ref().map(|c| c.uuid)
+    }
+
+    /// Subscribes to whichever of the status/UART-TX characteristics this board exposes and
+    /// returns the peripheral's single notification stream, so callers demux by
+    /// `ValueNotification::uuid` instead of each maintaining their own `notifications()` stream -
+    /// btleplug backends only promise one live stream per peripheral.
+    pub async fn subscribe_notifications(
+        &self,
+    ) -> anyhow::Result<impl Stream<Item = ValueNotification>> {
+        if let Some(status_char) = &self.status_char {
+            self.peripheral.subscribe(status_char).await?;
+        }
+        if let Some(uart_tx_char) = &self.uart_tx_char {
+            self.peripheral.subscribe(uart_tx_char).await?;
+        }
+
+        Ok(self.peripheral.notifications().await?)
+    }
+}
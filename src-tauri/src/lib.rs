@@ -1,14 +1,24 @@
+mod device_store;
+mod esp_handler;
+
 use std::time::Duration;
 
 use anyhow;
-use btleplug::api::{Central, Manager as _, Peripheral, ScanFilter, WriteType};
+use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral, PeripheralId, ScanFilter};
 use btleplug::platform::Manager;
+use esp_handler::EspHandler;
+use futures::StreamExt;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use tauri::{AppHandle, Emitter};
 use tokio::sync::RwLock;
 use tokio::time;
-use uuid::Uuid;
+
+const ESP_BLE_NAME: &str = "esp-bluedroid LED Example";
+
+/// How long to wait for a targeted reconnect to `KNOWN_DEVICE` to succeed before falling back to
+/// matching any nearby device by `ESP_BLE_NAME` instead.
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Serialize, Deserialize, Debug)]
 struct LedConfiguration {
@@ -17,6 +27,29 @@ struct LedConfiguration {
     enabled: bool,
 }
 
+#[derive(Serialize, Debug)]
+struct ScanResult {
+    /// `PeripheralId` (as its `Debug` string) — pass this back to `connect_device` rather than
+    /// `address`, which CoreBluetooth never populates with a real MAC.
+    id: String,
+    address: String,
+    local_name: String,
+    rssi: Option<i16>,
+}
+
+#[derive(Serialize, Debug)]
+struct BleError {
+    kind: String,
+    message: String,
+}
+
+/// `PeripheralId` is platform-specific and not `Serialize`/`FromStr`, so we persist and compare
+/// it as this `Debug` string instead of the `BDAddr` (which CoreBluetooth never populates with
+/// a real MAC address).
+fn peripheral_id_string(id: &PeripheralId) -> String {
+    format!("{:?}", id)
+}
+
 lazy_static! {
     pub static ref TOKIO_RUNTIME: tokio::runtime::Runtime = {
         let mut builder = tokio::runtime::Builder::new_multi_thread();
@@ -29,111 +62,224 @@ lazy_static! {
     static ref ADAPTER_LIST: Vec<btleplug::platform::Adapter> =
         TOKIO_RUNTIME.block_on(MANAGER.adapters()).unwrap();
     pub static ref ADAPTER: &'static btleplug::platform::Adapter = &ADAPTER_LIST[0];
-    pub static ref ESP_PERIPHERAL: RwLock<Option<btleplug::platform::Peripheral>> =
-        RwLock::new(None);
+    pub static ref ESP_HANDLER: RwLock<Option<EspHandler>> = RwLock::new(None);
     pub static ref APP_HANDLE: RwLock<Option<AppHandle>> = RwLock::new(None);
+    /// `PeripheralId` (as its `Debug` string) of the last device we successfully connected to,
+    /// loaded from disk on startup so we can target it directly instead of relying purely on
+    /// the (possibly ambiguous) local name.
+    pub static ref KNOWN_DEVICE: RwLock<Option<String>> = RwLock::new(None);
+    /// Id of the device a caller (`ble_monitoring`'s discovery-driven connect, whether to
+    /// `KNOWN_DEVICE` or by `ESP_BLE_NAME`, or `connect_device`) just asked us to connect to. `ble_monitoring` only adopts a `DeviceConnected` event as the active ESP
+    /// peripheral when it matches this id.
+    pub static ref TARGET_DEVICE: RwLock<Option<PeripheralId>> = RwLock::new(None);
+    /// Whether `ble_monitoring` may connect to a `DeviceDiscovered` peripheral purely by
+    /// `ESP_BLE_NAME`, rather than requiring it to match `KNOWN_DEVICE`. Starts `false` whenever
+    /// there's a pinned `KNOWN_DEVICE` to attempt a targeted reconnect to first; flips to `true`
+    /// once that reconnect times out (see `RECONNECT_TIMEOUT`).
+    static ref NAME_FALLBACK_ENABLED: RwLock<bool> = RwLock::new(false);
 }
 
-const ESP_BLE_NAME: &str = "esp-bluedroid LED Example";
+async fn emit_connection_status(connected: bool) -> anyhow::Result<()> {
+    APP_HANDLE
+        .read()
+        .await
+        .as_ref()
+        .unwrap()
+        .emit("connection-status", connected)?;
 
-pub async fn ble_monitoring() -> anyhow::Result<()> {
-    println!("Starting BLE monitoring...");
+    Ok(())
+}
 
-    loop {
-        time::sleep(Duration::from_secs(3)).await;
+/// Surfaces a BLE failure to the frontend as a structured `ble-error` event, so the UI can show
+/// actionable feedback instead of the caller silently giving up.
+async fn emit_ble_error(kind: &str, message: impl Into<String>) {
+    let error = BleError {
+        kind: kind.to_string(),
+        message: message.into(),
+    };
 
-        if ESP_PERIPHERAL.read().await.is_some() {
-            println!("ESP peripheral is already connected, checking connection status...");
+    if let Some(app) = APP_HANDLE.read().await.as_ref() {
+        if let Err(err) = app.emit("ble-error", error) {
+            eprintln!("Failed to emit ble-error event: {}", err);
+        }
+    }
+}
 
-            let is_connected = ESP_PERIPHERAL
-                .read()
-                .await
-                .as_ref()
-                .unwrap()
-                .is_connected()
-                .await?;
+/// Drives connection state off btleplug's `CentralEvent` stream instead of polling, so
+/// `connection-status` reflects real adapter transitions. A fresh install has no `KNOWN_DEVICE`,
+/// so discovery matches `ESP_BLE_NAME` right away; once we're pinned to a device, reconnects to
+/// it happen as soon as it's seen again, falling back to the `ESP_BLE_NAME` match only if that
+/// targeted reconnect doesn't land within `RECONNECT_TIMEOUT`.
+pub async fn ble_monitoring() -> anyhow::Result<()> {
+    println!("Starting BLE monitoring...");
 
-            if is_connected {
-                println!("ESP peripheral is connected, skipping scan...");
-                APP_HANDLE
-                    .read()
-                    .await
-                    .as_ref()
-                    .unwrap()
-                    .emit("connection-status", true)?;
-            } else {
-                println!("Sending event of disconnected");
-                *ESP_PERIPHERAL.write().await = None;
+    ADAPTER
+        .start_scan(ScanFilter::default())
+        .await
+        .expect("Can't scan BLE adapter for connected devices...");
 
-                APP_HANDLE
-                    .read()
-                    .await
-                    .as_ref()
-                    .unwrap()
-                    .emit("connection-status", false)?;
+    if KNOWN_DEVICE.read().await.is_some() {
+        tokio::spawn(async {
+            time::sleep(RECONNECT_TIMEOUT).await;
+            if ESP_HANDLER.read().await.is_none() {
+                println!("Targeted reconnect timed out, falling back to name-based scanning");
+                *NAME_FALLBACK_ENABLED.write().await = true;
             }
+        });
+    } else {
+        *NAME_FALLBACK_ENABLED.write().await = true;
+    }
 
-            continue;
-        }
-
-        println!("Starting scan on {}...", ADAPTER.adapter_info().await?);
-        ADAPTER
-            .start_scan(ScanFilter::default())
-            .await
-            .expect("Can't scan BLE adapter for connected devices...");
-        time::sleep(Duration::from_secs(1)).await;
+    let mut events = ADAPTER.events().await?;
 
-        let peripherals = ADAPTER.peripherals().await?;
-        let mut found = false;
+    while let Some(event) = events.next().await {
+        match event {
+            CentralEvent::DeviceDiscovered(id) => {
+                let peripheral = ADAPTER.peripheral(&id).await?;
+                if peripheral.is_connected().await? {
+                    continue;
+                }
 
-        for peripheral in peripherals.iter() {
-            let properties = peripheral.properties().await?;
-            let is_connected = peripheral.is_connected().await?;
-            let local_name = properties
-                .unwrap()
-                .local_name
-                .unwrap_or(String::from("(peripheral name unknown)"));
+                let known_device = KNOWN_DEVICE.read().await.clone();
+                let is_known_device = known_device.as_deref() == Some(peripheral_id_string(&id).as_str());
+                let name_fallback_enabled = *NAME_FALLBACK_ENABLED.read().await;
 
-            if local_name == ESP_BLE_NAME {
-                found = true;
+                let should_connect = if is_known_device {
+                    true
+                } else if name_fallback_enabled {
+                    peripheral
+                        .properties()
+                        .await?
+                        .and_then(|props| props.local_name)
+                        .is_some_and(|name| name == ESP_BLE_NAME)
+                } else {
+                    false
+                };
 
-                if !is_connected {
-                    println!("Connecting to peripheral {:?}...", &local_name);
+                if should_connect {
+                    println!("Discovered {:?}, connecting...", id);
+                    *TARGET_DEVICE.write().await = Some(id);
                     if let Err(err) = peripheral.connect().await {
-                        eprintln!("Error connecting to peripheral, skipping: {}", err);
-                        continue;
+                        let message = format!("Error connecting to peripheral: {}", err);
+                        eprintln!("{}", message);
+                        emit_ble_error("connect-failed", message).await;
                     }
                 }
+            }
+            CentralEvent::DeviceConnected(id) => {
+                let is_target = TARGET_DEVICE.read().await.as_ref() == Some(&id);
+                if !is_target {
+                    continue;
+                }
+
+                let peripheral = ADAPTER.peripheral(&id).await?;
+                if let Err(err) = adopt_connected_peripheral(peripheral).await {
+                    let message = format!("Error setting up ESP handler: {}", err);
+                    eprintln!("{}", message);
+                    emit_ble_error("setup-failed", message).await;
+                }
+            }
+            CentralEvent::DeviceDisconnected(id) => {
+                let is_esp = ESP_HANDLER
+                    .read()
+                    .await
+                    .as_ref()
+                    .is_some_and(|handler| handler.peripheral().id() == id);
 
-                let is_connected = peripheral.is_connected().await?;
-                println!(
-                    "Now connected ({:?}) to peripheral {:?}...",
-                    is_connected, &local_name
-                );
-                *ESP_PERIPHERAL.write().await = Some(peripheral.clone());
-
-                if is_connected {
-                    println!("Sending event of connected");
-                    APP_HANDLE
-                        .read()
-                        .await
-                        .as_ref()
-                        .unwrap()
-                        .emit("connection-status", true)?;
+                if !is_esp {
+                    continue;
                 }
+
+                println!("ESP peripheral disconnected");
+                *ESP_HANDLER.write().await = None;
+                emit_connection_status(false).await?;
             }
+            _ => {}
         }
+    }
+
+    Ok(())
+}
+
+/// Builds the `EspHandler` for a connected peripheral, spawns its notification pumps, persists
+/// it as the known device, and emits `connection-status`. Shared by `ble_monitoring`'s
+/// `DeviceConnected` handling and `connect_device`, which needs to adopt a peripheral that's
+/// already connected by the time it runs, whether that's because `ble_monitoring`'s own
+/// discovery-driven connect got there first or because `peripheral.connect()` itself just succeeded (no guarantee a
+/// `DeviceConnected` event follows on every platform).
+async fn adopt_connected_peripheral(peripheral: btleplug::platform::Peripheral) -> anyhow::Result<()> {
+    let already_adopted = ESP_HANDLER
+        .read()
+        .await
+        .as_ref()
+        .is_some_and(|handler| handler.peripheral().id() == peripheral.id());
+    if already_adopted {
+        return Ok(());
+    }
 
-        if !found {
-            *ESP_PERIPHERAL.write().await = None;
+    let local_name = peripheral
+        .properties()
+        .await?
+        .and_then(|props| props.local_name)
+        .unwrap_or(String::from("(peripheral name unknown)"));
+    let id = peripheral_id_string(&peripheral.id());
+
+    println!("Now connected to peripheral {:?}...", &local_name);
+    let handler = EspHandler::connect(peripheral).await?;
+    if handler.status_uuid().is_some() || handler.uart_tx_uuid().is_some() {
+        tokio::spawn(pump_notifications(handler.clone()));
+    }
+    *ESP_HANDLER.write().await = Some(handler);
+
+    *KNOWN_DEVICE.write().await = Some(id.clone());
+    if let Some(app) = APP_HANDLE.read().await.as_ref() {
+        if let Err(err) = device_store::save(app, &id) {
+            eprintln!("Failed to persist known device: {}", err);
+        }
+    }
+
+    emit_connection_status(true).await?;
+
+    Ok(())
+}
+
+/// Pumps the single notification stream for whichever of the LED status / Nordic-UART-TX
+/// characteristics this board exposes, dispatching each notification by `uuid` to the matching
+/// UI event: decoded `LedConfiguration` as `led-status`, so the frontend sees the board's actual
+/// state instead of assuming a prior write succeeded; raw UTF-8 lines as `command-line`, for a
+/// free-form debug/command console alongside the structured LED config write. A single shared
+/// stream (rather than one `notifications()` call per characteristic) matches what btleplug
+/// backends actually support: one live notification stream per peripheral.
+async fn pump_notifications(handler: EspHandler) -> anyhow::Result<()> {
+    let status_uuid = handler.status_uuid();
+    let uart_tx_uuid = handler.uart_tx_uuid();
+    let mut notifications = handler.subscribe_notifications().await?;
+
+    while let Some(notification) = notifications.next().await {
+        if Some(notification.uuid) == status_uuid {
+            let Ok((status, _)) = bincode::serde::decode_from_slice::<LedConfiguration, _>(
+                &notification.value,
+                bincode::config::standard(),
+            ) else {
+                eprintln!("Failed to decode LED status notification");
+                continue;
+            };
+
+            APP_HANDLE
+                .read()
+                .await
+                .as_ref()
+                .unwrap()
+                .emit("led-status", status)?;
+        } else if Some(notification.uuid) == uart_tx_uuid {
+            let line = String::from_utf8_lossy(&notification.value).into_owned();
 
-            println!("Sending event of disconnected");
             APP_HANDLE
                 .read()
                 .await
                 .as_ref()
                 .unwrap()
-                .emit("connection-status", false)?;
+                .emit("command-line", line)?;
         }
     }
 
@@ -141,41 +287,121 @@ pub async fn ble_monitoring() -> anyhow::Result<()> {
 }
 
 #[tauri::command]
-async fn update_led_config(led_config: LedConfiguration) {
+async fn send_command(text: String) -> Result<(), String> {
+    let esp_lock = ESP_HANDLER.read().await;
+    let Some(handler) = esp_lock.as_ref() else {
+        let message = "ESP peripheral not connected".to_string();
+        emit_ble_error("not-connected", message.clone()).await;
+        return Err(message);
+    };
+
+    if let Err(err) = handler.send_command(&text).await {
+        let message = err.to_string();
+        emit_ble_error("write-failed", message.clone()).await;
+        return Err(message);
+    }
+
+    Ok(())
+}
+
+#[tauri::command]
+async fn update_led_config(led_config: LedConfiguration) -> Result<(), String> {
     println!("Updating LED configuration: {:?}", led_config);
 
-    let esp_lock = ESP_PERIPHERAL.read().await;
-    let Some(esp) = esp_lock.as_ref() else {
-        println!("ESP peripheral not found!");
-        return;
+    let esp_lock = ESP_HANDLER.read().await;
+    let Some(handler) = esp_lock.as_ref() else {
+        let message = "ESP peripheral not connected".to_string();
+        emit_ble_error("not-connected", message.clone()).await;
+        return Err(message);
     };
-    if let Err(err) = esp.discover_services().await {
-        println!("Error discovering services: {}", err);
-        return;
+
+    if let Err(err) = handler.write_led_config(&led_config).await {
+        let message = err.to_string();
+        emit_ble_error("write-failed", message.clone()).await;
+        return Err(message);
     }
 
-    let characteristics = esp.characteristics();
-    let Some(led_config_char) = characteristics
-        .iter()
-        .find(|el| el.uuid == Uuid::from_u128(42424242))
-    else {
-        println!("LED configuration characteristic not found!");
-        return;
-    };
+    Ok(())
+}
+
+#[tauri::command]
+async fn scan_devices(duration_secs: u64) -> Result<Vec<ScanResult>, String> {
+    ADAPTER
+        .start_scan(ScanFilter::default())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    time::sleep(Duration::from_secs(duration_secs)).await;
+
+    let peripherals = ADAPTER.peripherals().await.map_err(|err| err.to_string())?;
+    let mut results = Vec::with_capacity(peripherals.len());
+
+    for peripheral in peripherals {
+        let Some(properties) = peripheral.properties().await.map_err(|err| err.to_string())?
+        else {
+            continue;
+        };
+
+        results.push(ScanResult {
+            id: peripheral_id_string(&peripheral.id()),
+            address: peripheral.address().to_string(),
+            local_name: properties
+                .local_name
+                .unwrap_or(String::from("(peripheral name unknown)")),
+            rssi: properties.rssi,
+        });
+    }
+
+    Ok(results)
+}
+
+#[tauri::command]
+async fn connect_device(id: String) -> Result<(), String> {
+    let connect_result = connect_device_inner(id).await;
+
+    if let Err(message) = &connect_result {
+        emit_ble_error("connect-failed", message.clone()).await;
+    }
 
-    let Ok(new_config_bytes) =
-        bincode::serde::encode_to_vec(led_config, bincode::config::standard())
+    connect_result
+}
+
+async fn connect_device_inner(id: String) -> Result<(), String> {
+    let peripherals = ADAPTER.peripherals().await.map_err(|err| err.to_string())?;
+    let Some(peripheral) = peripherals
+        .into_iter()
+        .find(|p| peripheral_id_string(&p.id()) == id)
     else {
-        println!("Failed to serialize LED configuration!");
-        return;
+        return Err("Device not found, try scanning again".to_string());
     };
-    esp.write(
-        led_config_char,
-        &new_config_bytes,
-        WriteType::WithoutResponse,
-    )
-    .await
-    .unwrap();
+
+    *TARGET_DEVICE.write().await = Some(peripheral.id());
+
+    if !peripheral
+        .is_connected()
+        .await
+        .map_err(|err| err.to_string())?
+    {
+        peripheral
+            .connect()
+            .await
+            .map_err(|err| err.to_string())?;
+    }
+
+    // Either it was already connected (e.g. by `ble_monitoring`'s discovery-driven connect), or
+    // `connect()` just succeeded - adopt it directly rather than relying on a `DeviceConnected`
+    // event, which isn't guaranteed to fire on every platform.
+    adopt_connected_peripheral(peripheral)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+#[tauri::command]
+async fn forget_device(app: AppHandle) -> Result<(), String> {
+    device_store::clear(&app).map_err(|err| err.to_string())?;
+    *KNOWN_DEVICE.write().await = None;
+
+    Ok(())
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -184,10 +410,22 @@ pub fn run() {
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![update_led_config])
+        .invoke_handler(tauri::generate_handler![
+            update_led_config,
+            send_command,
+            scan_devices,
+            connect_device,
+            forget_device
+        ])
         .setup(|app| {
             TOKIO_RUNTIME.block_on(async {
                 *APP_HANDLE.write().await = Some(app.handle().clone());
+
+                match device_store::load(app.handle()) {
+                    Ok(Some(id)) => *KNOWN_DEVICE.write().await = Some(id),
+                    Ok(None) => {}
+                    Err(err) => eprintln!("Failed to load persisted device: {}", err),
+                }
             });
 
             TOKIO_RUNTIME.spawn(ble_monitoring());